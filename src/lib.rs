@@ -13,6 +13,14 @@
 //!   will automatically be disabled for all cross compiles.
 //! * `FOO_NO_PKG_CONFIG` - if set, this will disable running `pkg-config` when
 //!   probing for the library named `foo`.
+//! * `PKG_CONFIG` - if set, this is the name (or path) of the `pkg-config`
+//!   binary to run. `<TARGET>_PKG_CONFIG` and `PKG_CONFIG_<target>` (with
+//!   the target triple upper-cased and `-` replaced by `_`) take priority
+//!   over this for cross compiles, allowing a triple-prefixed binary such as
+//!   `arm-linux-gnueabihf-pkg-config` to be selected automatically.
+//! * `PKG_CONFIG_PATH`, `PKG_CONFIG_LIBDIR`, `PKG_CONFIG_SYSROOT_DIR` - these
+//!   are forwarded to `pkg-config` as-is, and may also be set per-target in
+//!   the same `<TARGET>_FOO` / `FOO_<target>` style described above.
 //!
 //! There are also a number of environment variables which can configure how a
 //! library is linked to (dynamically vs statically). These variables control
@@ -55,10 +63,16 @@
 #![feature(convert)]
 
 use std::ascii::AsciiExt;
+use std::collections::HashMap;
 use std::env;
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::ops::Bound;
 use std::path::{PathBuf, Path};
-use std::process::Command;
+use std::process::{Command, Output};
 use std::str;
 
 pub fn target_supported() -> bool {
@@ -66,10 +80,92 @@ pub fn target_supported() -> bool {
         env::var_os("PKG_CONFIG_ALLOW_CROSS").is_some()
 }
 
+/// Represents all the ways probing for a library can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// Aborted because of `*_NO_PKG_CONFIG` environment variable.
+    ///
+    /// Contains the name of the responsible environment variable.
+    EnvNoPkgConfig(String),
+
+    /// Cross compilation detected, and not allowed.
+    CrossCompilation,
+
+    /// Failed to run `pkg-config`.
+    ///
+    /// Contains the command and the cause.
+    Command {
+        command: String,
+        cause: io::Error,
+    },
+
+    /// `pkg-config` did not exit successfully.
+    ///
+    /// Contains the command and the output.
+    Failure {
+        command: String,
+        output: Output,
+    },
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::EnvNoPkgConfig(_) => "aborted because environment variable requested it",
+            Error::CrossCompilation => "pkg-config doesn't handle cross compilation",
+            Error::Command { .. } => "failed to run pkg-config",
+            Error::Failure { .. } => "pkg-config did not exit successfully",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EnvNoPkgConfig(ref name) => {
+                write!(f, "Aborted because {} is set", name)
+            }
+            Error::CrossCompilation => {
+                write!(f, "pkg-config doesn't handle cross compilation. \
+                            Use PKG_CONFIG_ALLOW_CROSS=1 to override")
+            }
+            Error::Command { ref command, ref cause } => {
+                write!(f, "failed to run `{}`: {}", command, cause)
+            }
+            Error::Failure { ref command, ref output } => {
+                let stdout = str::from_utf8(&output.stdout).unwrap();
+                let stderr = str::from_utf8(&output.stderr).unwrap();
+                try!(write!(f, "`{}` did not exit successfully: {}", command,
+                            output.status));
+                if stdout.len() > 0 {
+                    try!(write!(f, "\n--- stdout\n{}", stdout));
+                }
+                if stderr.len() > 0 {
+                    try!(write!(f, "\n--- stderr\n{}", stderr));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     statik: Option<bool>,
-    atleast_version: Option<String>,
+    version: Option<Version>,
+    cross_compile: Option<bool>,
+    cargo_metadata: bool,
+    env_metadata: bool,
+}
+
+/// The version constraint to pass to `pkg-config`, set by `atleast_version`,
+/// `exactly_version`, or `range_version`. Only the most recently called
+/// setter takes effect.
+#[derive(Clone)]
+enum Version {
+    AtLeast(String),
+    Exactly(String),
+    Range(Bound<String>, Bound<String>),
 }
 
 #[derive(Debug)]
@@ -79,11 +175,14 @@ pub struct Library {
     pub frameworks: Vec<String>,
     pub framework_paths: Vec<PathBuf>,
     pub include_paths: Vec<PathBuf>,
+    pub defines: HashMap<String, Option<String>>,
+    pub cflags: Vec<String>,
+    pub ld_args: Vec<Vec<String>>,
     _priv: (),
 }
 
 /// Simple shortcut for using all default options for finding a library.
-pub fn find_library(name: &str) -> Result<Library, String> {
+pub fn find_library(name: &str) -> Result<Library, Error> {
     Config::new().find(name)
 }
 
@@ -93,7 +192,10 @@ impl Config {
     pub fn new() -> Config {
         Config {
             statik: None,
-            atleast_version: None,
+            version: None,
+            cross_compile: None,
+            cargo_metadata: true,
+            env_metadata: false,
         }
     }
 
@@ -107,8 +209,59 @@ impl Config {
     }
 
     /// Indicate that the library must be at least version `vers`.
+    ///
+    /// Overrides any previous call to `atleast_version`, `exactly_version`,
+    /// or `range_version`.
     pub fn atleast_version(&mut self, vers: &str) -> &mut Config {
-        self.atleast_version = Some(vers.to_string());
+        self.version = Some(Version::AtLeast(vers.to_string()));
+        self
+    }
+
+    /// Indicate that the library must be exactly version `vers`.
+    ///
+    /// Overrides any previous call to `atleast_version`, `exactly_version`,
+    /// or `range_version`.
+    pub fn exactly_version(&mut self, vers: &str) -> &mut Config {
+        self.version = Some(Version::Exactly(vers.to_string()));
+        self
+    }
+
+    /// Indicate that the library's version must fall within `low` and
+    /// `high`, each of which may be inclusive, exclusive, or unbounded.
+    ///
+    /// Overrides any previous call to `atleast_version`, `exactly_version`,
+    /// or `range_version`.
+    pub fn range_version(&mut self, low: Bound<&str>, high: Bound<&str>) -> &mut Config {
+        self.version = Some(Version::Range(to_owned_bound(low), to_owned_bound(high)));
+        self
+    }
+
+    /// Indicate whether `pkg-config` should be run despite the host and
+    /// target appearing not to match, overriding `PKG_CONFIG_ALLOW_CROSS`.
+    pub fn allow_cross(&mut self, allow: bool) -> &mut Config {
+        self.cross_compile = Some(allow);
+        self
+    }
+
+    /// Indicate whether `cargo:rustc-link-*` and similar metadata lines
+    /// should be printed to stdout. Defaults to `true`.
+    ///
+    /// Set this to `false` when the caller wants to inspect the returned
+    /// `Library` and decide what to emit itself.
+    pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Config {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Indicate whether a `cargo:rerun-if-env-changed=` line should be
+    /// printed for every environment variable this crate consults.
+    /// Defaults to `false`.
+    ///
+    /// This setting only takes effect when `cargo_metadata` is also `true`;
+    /// disabling `cargo_metadata` suppresses all `cargo:` output, including
+    /// these lines.
+    pub fn env_metadata(&mut self, env_metadata: bool) -> &mut Config {
+        self.env_metadata = env_metadata;
         self
     }
 
@@ -116,84 +269,59 @@ impl Config {
     ///
     /// This will use all configuration previously set to specify how
     /// `pkg-config` is run.
-    pub fn find(&self, name: &str) -> Result<Library, String> {
+    pub fn find(&self, name: &str) -> Result<Library, Error> {
+        let target = env::var("TARGET").ok();
+        let cross_ok = match self.cross_compile {
+            Some(allow) => allow || env::var("HOST") == env::var("TARGET"),
+            None => target_supported(),
+        };
+
         if env::var_os(&format!("{}_NO_PKG_CONFIG", envify(name))).is_some() {
-            return Err(format!("pkg-config requested to be aborted for {}", name))
-        } else if !target_supported() {
-            return Err("pkg-config doesn't handle cross compilation. Use \
-                        PKG_CONFIG_ALLOW_CROSS=1 to override".to_string());
+            return Err(Error::EnvNoPkgConfig(format!("{}_NO_PKG_CONFIG", envify(name))))
+        } else if !cross_ok {
+            return Err(Error::CrossCompilation);
         }
 
-        let mut cmd = Command::new("pkg-config");
+        let mut cmd = Command::new(find_pkg_config_exe(&target));
         let statik = self.statik.unwrap_or(infer_static(name));
         if statik {
             cmd.arg("--static");
         }
+        for var in &["PKG_CONFIG_PATH", "PKG_CONFIG_LIBDIR", "PKG_CONFIG_SYSROOT_DIR"] {
+            if let Some(val) = env_for_target(var, &target) {
+                cmd.env(var, val);
+            }
+        }
         cmd.arg("--libs").arg("--cflags")
            .env("PKG_CONFIG_ALLOW_SYSTEM_LIBS", "1");
-        match self.atleast_version {
-            Some(ref v) => { cmd.arg(&format!("{} >= {}", name, v)); }
-            None => { cmd.arg(name); }
+        let version_args = version_args(name, &self.version);
+        if version_args.is_empty() {
+            cmd.arg(name);
+        } else {
+            for arg in &version_args {
+                cmd.arg(arg);
+            }
         }
         let out = try!(cmd.output().map_err(|e| {
-            format!("failed to run `{:?}`: {}", cmd, e)
+            Error::Command { command: format!("{:?}", cmd), cause: e }
         }));
-        let stdout = str::from_utf8(&out.stdout).unwrap();
-        let stderr = str::from_utf8(&out.stderr).unwrap();
         if !out.status.success() {
-            let mut msg = format!("`{:?}` did not exit successfully: {}", cmd,
-                                  out.status);
-            if stdout.len() > 0 {
-                msg.push_str("\n--- stdout\n");
-                msg.push_str(stdout);
-            }
-            if stderr.len() > 0 {
-                msg.push_str("\n--- stderr\n");
-                msg.push_str(stderr);
-            }
-            return Err(msg)
-        }
-
-        let mut ret = Library {
-            libs: Vec::new(),
-            link_paths: Vec::new(),
-            include_paths: Vec::new(),
-            frameworks: Vec::new(),
-            framework_paths: Vec::new(),
-            _priv: (),
-        };
-        let mut dirs = Vec::new();
-        let parts = stdout.split(' ').filter(|l| l.len() > 2)
-                          .map(|arg| (&arg[0..2], &arg[2..]))
-                          .collect::<Vec<_>>();
-        for &(flag, val) in parts.iter() {
-            if flag == "-L" {
-                println!("cargo:rustc-link-search=native={}", val);
-                dirs.push(PathBuf::from(val));
-                ret.link_paths.push(PathBuf::from(val));
-            } else if flag == "-F" {
-                println!("cargo:rustc-link-search=framework={}", val);
-                ret.framework_paths.push(PathBuf::from(val));
-            } else if flag == "-I" {
-                ret.include_paths.push(PathBuf::from(val));
-            }
-        }
-        for &(flag, val) in parts.iter() {
-            if flag == "-l" {
-                ret.libs.push(val.to_string());
-                if statik && !is_system_lib(val, &dirs) {
-                    println!("cargo:rustc-link-lib=static={}", val);
-                } else {
-                    println!("cargo:rustc-link-lib={}", val);
-                }
+            return Err(Error::Failure { command: format!("{:?}", cmd), output: out })
+        }
+        let stdout = str::from_utf8(&out.stdout).unwrap();
+
+        let words = shell_split(stdout);
+        let (ret, metadata) = build_library(&words, statik);
+
+        if self.cargo_metadata {
+            for line in &metadata {
+                println!("cargo:{}", line);
             }
         }
-        let mut iter = stdout.split(' ');
-        while let Some(part) = iter.next() {
-            if part != "-framework" { continue }
-            if let Some(lib) = iter.next() {
-                println!("cargo:rustc-link-lib=framework={}", lib);
-                ret.frameworks.push(lib.to_string());
+
+        if self.cargo_metadata && self.env_metadata {
+            for var in env_vars_consulted(name, &target) {
+                println!("cargo:rerun-if-env-changed={}", var);
             }
         }
 
@@ -201,6 +329,37 @@ impl Config {
     }
 }
 
+/// List the names of every environment variable `find` consults while
+/// probing for `name`, so that `cargo:rerun-if-env-changed` can be emitted
+/// for each.
+fn env_vars_consulted(name: &str, target: &Option<String>) -> Vec<String> {
+    let name = envify(name);
+    let mut vars = vec![
+        format!("{}_NO_PKG_CONFIG", name),
+        format!("{}_STATIC", name),
+        format!("{}_DYNAMIC", name),
+        String::from("HOST"),
+        String::from("TARGET"),
+        String::from("PKG_CONFIG_ALLOW_CROSS"),
+        String::from("PKG_CONFIG_ALL_STATIC"),
+        String::from("PKG_CONFIG_ALL_DYNAMIC"),
+        String::from("PKG_CONFIG"),
+        String::from("PKG_CONFIG_PATH"),
+        String::from("PKG_CONFIG_LIBDIR"),
+        String::from("PKG_CONFIG_SYSROOT_DIR"),
+    ];
+    if let Some(ref target) = *target {
+        let target = envify(target);
+        vars.push(format!("{}_PKG_CONFIG", target));
+        vars.push(format!("PKG_CONFIG_{}", target));
+        for var in &["PKG_CONFIG_PATH", "PKG_CONFIG_LIBDIR", "PKG_CONFIG_SYSROOT_DIR"] {
+            vars.push(format!("{}_{}", target, var));
+            vars.push(format!("{}_{}", var, target));
+        }
+    }
+    vars
+}
+
 fn infer_static(name: &str) -> bool {
     let name = envify(name);
     if env::var_os(&format!("{}_STATIC", name)).is_some() {
@@ -216,11 +375,235 @@ fn infer_static(name: &str) -> bool {
     }
 }
 
+fn to_owned_bound(bound: Bound<&str>) -> Bound<String> {
+    match bound {
+        Bound::Included(v) => Bound::Included(v.to_string()),
+        Bound::Excluded(v) => Bound::Excluded(v.to_string()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Render one side of a version range as a `pkg-config` requirement, e.g.
+/// `name >= 1.2`, using `inclusive_op` for `Bound::Included` and
+/// `exclusive_op` for `Bound::Excluded`.
+fn bound_arg(name: &str, bound: &Bound<String>, inclusive_op: &str,
+             exclusive_op: &str) -> Option<String> {
+    match *bound {
+        Bound::Included(ref v) => Some(format!("{} {} {}", name, inclusive_op, v)),
+        Bound::Excluded(ref v) => Some(format!("{} {} {}", name, exclusive_op, v)),
+        Bound::Unbounded => None,
+    }
+}
+
+/// Assemble the requirement arguments to pass to `pkg-config` for `name`
+/// given the configured version constraint. An empty result means no
+/// constraint was configured and the bare package name should be used.
+fn version_args(name: &str, version: &Option<Version>) -> Vec<String> {
+    match *version {
+        Some(Version::AtLeast(ref v)) => vec![format!("{} >= {}", name, v)],
+        Some(Version::Exactly(ref v)) => vec![format!("{} = {}", name, v)],
+        Some(Version::Range(ref low, ref high)) => {
+            let mut args = Vec::new();
+            if let Some(arg) = bound_arg(name, low, ">=", ">") {
+                args.push(arg);
+            }
+            if let Some(arg) = bound_arg(name, high, "<=", "<") {
+                args.push(arg);
+            }
+            args
+        }
+        None => Vec::new(),
+    }
+}
+
 fn envify(name: &str) -> String {
     name.chars().map(|c| c.to_ascii_uppercase()).map(|c| if c == '-' {'_'} else {c})
         .collect()
 }
 
+/// Determine which `pkg-config` binary to run, allowing a target-specific
+/// (possibly triple-prefixed) override via `<TARGET>_PKG_CONFIG` or
+/// `PKG_CONFIG_<target>`, falling back to a global `PKG_CONFIG` override and
+/// finally the bare `pkg-config` name.
+fn find_pkg_config_exe(target: &Option<String>) -> String {
+    let exe = target.as_ref().and_then(|target| {
+        env::var_os(&format!("{}_PKG_CONFIG", envify(target)))
+            .or_else(|| env::var_os(&format!("PKG_CONFIG_{}", envify(target))))
+    }).or_else(|| env::var_os("PKG_CONFIG"));
+
+    match exe {
+        Some(exe) => exe.to_string_lossy().into_owned(),
+        None => String::from("pkg-config"),
+    }
+}
+
+/// Look up an environment variable, preferring a target-specific override
+/// (`<TARGET>_FOO` or `FOO_<target>`) over the bare variable name.
+fn env_for_target(var: &str, target: &Option<String>) -> Option<OsString> {
+    if let Some(ref target) = *target {
+        if let Some(val) = env::var_os(&format!("{}_{}", envify(target), var)) {
+            return Some(val);
+        }
+        if let Some(val) = env::var_os(&format!("{}_{}", var, envify(target))) {
+            return Some(val);
+        }
+    }
+    env::var_os(var)
+}
+
+/// Split `pkg-config`'s `--libs --cflags` output into tokens, honoring its
+/// shell-like quoting so that paths containing spaces or escapes survive
+/// intact.
+///
+/// A backslash escapes the following character literally. Single quotes open
+/// a literal span terminated by the next single quote. Double quotes open a
+/// span in which only `\`, `` ` ``, `$`, and `"` may be escaped with a
+/// backslash. Unquoted whitespace terminates the current token and is
+/// otherwise skipped, so consecutive delimiters never produce empty tokens.
+fn shell_split(output: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut cur = String::new();
+    let mut in_token = false;
+    let mut chars = output.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    cur.push(next);
+                    in_token = true;
+                }
+            }
+            '\'' => {
+                in_token = true;
+                for c2 in &mut chars {
+                    if c2 == '\'' { break }
+                    cur.push(c2);
+                }
+            }
+            '"' => {
+                in_token = true;
+                while let Some(c2) = chars.next() {
+                    if c2 == '"' {
+                        break
+                    } else if c2 == '\\' {
+                        match chars.peek() {
+                            Some(&next) if next == '\\' || next == '"' ||
+                                           next == '$' || next == '`' => {
+                                cur.push(next);
+                                chars.next();
+                            }
+                            _ => cur.push('\\'),
+                        }
+                    } else {
+                        cur.push(c2);
+                    }
+                }
+            }
+            ' ' | '\t' | '\n' => {
+                if in_token {
+                    tokens.push(cur.clone());
+                    cur.clear();
+                    in_token = false;
+                }
+            }
+            c => {
+                cur.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if in_token {
+        tokens.push(cur);
+    }
+    tokens
+}
+
+/// Classify the tokenized `--libs --cflags` output into a `Library`,
+/// returning it alongside the `cargo:` metadata lines (without the `cargo:`
+/// prefix) that `find` should print when metadata output is enabled.
+fn build_library(words: &[String], statik: bool) -> (Library, Vec<String>) {
+    let mut ret = Library {
+        libs: Vec::new(),
+        link_paths: Vec::new(),
+        include_paths: Vec::new(),
+        frameworks: Vec::new(),
+        framework_paths: Vec::new(),
+        defines: HashMap::new(),
+        cflags: Vec::new(),
+        ld_args: Vec::new(),
+        _priv: (),
+    };
+    let mut metadata = Vec::new();
+    let mut dirs = Vec::new();
+    let parts = words.iter().filter(|l| l.len() > 2)
+                      .map(|arg| (&arg[0..2], &arg[2..]))
+                      .collect::<Vec<_>>();
+    for &(flag, val) in parts.iter() {
+        if flag == "-L" {
+            metadata.push(format!("rustc-link-search=native={}", val));
+            dirs.push(PathBuf::from(val));
+            ret.link_paths.push(PathBuf::from(val));
+        } else if flag == "-F" {
+            metadata.push(format!("rustc-link-search=framework={}", val));
+            ret.framework_paths.push(PathBuf::from(val));
+        } else if flag == "-I" {
+            ret.include_paths.push(PathBuf::from(val));
+        } else if flag == "-D" {
+            let mut iter = val.splitn(2, '=');
+            let name = iter.next().unwrap_or("").to_string();
+            let value = iter.next().map(|v| v.to_string());
+            ret.defines.insert(name, value);
+        }
+    }
+    let mut skip_next = false;
+    for word in words.iter() {
+        if skip_next {
+            skip_next = false;
+            continue
+        }
+        if word == "-framework" {
+            skip_next = true;
+            continue
+        }
+        if word.starts_with("-Wl,") {
+            ret.ld_args.push(word.split(',').map(|a| a.to_string()).collect());
+            continue
+        }
+        if word.len() > 2 {
+            let flag = &word[0..2];
+            if flag == "-l" || flag == "-L" || flag == "-F" ||
+               flag == "-I" || flag == "-D" {
+                continue
+            }
+        }
+        ret.cflags.push(word.clone());
+    }
+    for &(flag, val) in parts.iter() {
+        if flag == "-l" {
+            ret.libs.push(val.to_string());
+            if statik && !is_system_lib(val, &dirs) {
+                metadata.push(format!("rustc-link-lib=static={}", val));
+            } else {
+                metadata.push(format!("rustc-link-lib={}", val));
+            }
+        }
+    }
+    let mut iter = words.iter();
+    while let Some(part) = iter.next() {
+        if part != "-framework" { continue }
+        if let Some(lib) = iter.next() {
+            metadata.push(format!("rustc-link-lib=framework={}", lib));
+            ret.frameworks.push(lib.to_string());
+        }
+    }
+    for arg in &ret.ld_args {
+        metadata.push(format!("rustc-link-arg={}", arg.join(",")));
+    }
+
+    (ret, metadata)
+}
+
 fn is_system_lib(name: &str, dirs: &[PathBuf]) -> bool {
     let libname = format!("lib{}.a", name);
     let root = Path::new("/usr");
@@ -228,3 +611,286 @@ fn is_system_lib(name: &str, dirs: &[PathBuf]) -> bool {
         !d.starts_with(root) && fs::metadata(&d.join(&libname)).is_ok()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn error_display_env_no_pkg_config() {
+        let e = Error::EnvNoPkgConfig("FOO_NO_PKG_CONFIG".to_string());
+        assert_eq!(e.to_string(), "Aborted because FOO_NO_PKG_CONFIG is set");
+    }
+
+    #[test]
+    fn error_display_cross_compilation() {
+        let e = Error::CrossCompilation;
+        assert!(e.to_string().contains("PKG_CONFIG_ALLOW_CROSS=1"));
+    }
+
+    #[test]
+    fn error_display_command_includes_cause() {
+        let cause = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let e = Error::Command { command: "pkg-config".to_string(), cause: cause };
+        let msg = e.to_string();
+        assert!(msg.starts_with("failed to run `pkg-config`: "));
+        assert!(msg.contains("no such file"));
+    }
+
+    #[test]
+    fn allow_cross_false_overrides_env_allow_cross() {
+        env::set_var("HOST", "my-test-host-unique-5");
+        env::set_var("TARGET", "my-test-target-unique-5");
+        env::set_var("PKG_CONFIG_ALLOW_CROSS", "1");
+
+        let result = Config::new().allow_cross(false).find("nonexistent-lib-unique-5");
+
+        env::remove_var("HOST");
+        env::remove_var("TARGET");
+        env::remove_var("PKG_CONFIG_ALLOW_CROSS");
+
+        match result {
+            Err(Error::CrossCompilation) => {}
+            other => panic!("expected CrossCompilation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn allow_cross_true_overrides_mismatched_host_and_target() {
+        env::set_var("HOST", "my-test-host-unique-6");
+        env::set_var("TARGET", "my-test-target-unique-6");
+        env::remove_var("PKG_CONFIG_ALLOW_CROSS");
+
+        let result = Config::new().allow_cross(true).find("nonexistent-lib-unique-6");
+
+        env::remove_var("HOST");
+        env::remove_var("TARGET");
+
+        // cross-compilation is explicitly allowed, so the call must get past
+        // the cross-compile gate; it still fails, but for a different reason.
+        match result {
+            Err(Error::CrossCompilation) => panic!("allow_cross(true) should bypass this check"),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn find_pkg_config_exe_defaults_to_bare_name() {
+        env::remove_var("PKG_CONFIG");
+        assert_eq!(find_pkg_config_exe(&None), "pkg-config");
+    }
+
+    #[test]
+    fn find_pkg_config_exe_honors_global_override() {
+        env::set_var("PKG_CONFIG", "/usr/bin/custom-pkg-config");
+        let result = find_pkg_config_exe(&None);
+        env::remove_var("PKG_CONFIG");
+        assert_eq!(result, "/usr/bin/custom-pkg-config");
+    }
+
+    #[test]
+    fn find_pkg_config_exe_prefers_target_pkg_config_over_global() {
+        let target = "my-test-target-unique-1";
+        env::set_var("PKG_CONFIG", "/usr/bin/global-pkg-config");
+        env::set_var("MY_TEST_TARGET_UNIQUE_1_PKG_CONFIG", "/usr/bin/target-pkg-config");
+        let result = find_pkg_config_exe(&Some(target.to_string()));
+        env::remove_var("PKG_CONFIG");
+        env::remove_var("MY_TEST_TARGET_UNIQUE_1_PKG_CONFIG");
+        assert_eq!(result, "/usr/bin/target-pkg-config");
+    }
+
+    #[test]
+    fn find_pkg_config_exe_honors_pkg_config_target_form() {
+        let target = "my-test-target-unique-2";
+        env::set_var("PKG_CONFIG_MY_TEST_TARGET_UNIQUE_2", "/usr/bin/other-pkg-config");
+        let result = find_pkg_config_exe(&Some(target.to_string()));
+        env::remove_var("PKG_CONFIG_MY_TEST_TARGET_UNIQUE_2");
+        assert_eq!(result, "/usr/bin/other-pkg-config");
+    }
+
+    #[test]
+    fn env_for_target_prefers_target_prefixed_var_over_global() {
+        let target = "my-test-target-unique-3";
+        env::set_var("PKG_CONFIG_PATH", "/global/path");
+        env::set_var("MY_TEST_TARGET_UNIQUE_3_PKG_CONFIG_PATH", "/target/path");
+        let result = env_for_target("PKG_CONFIG_PATH", &Some(target.to_string()));
+        env::remove_var("PKG_CONFIG_PATH");
+        env::remove_var("MY_TEST_TARGET_UNIQUE_3_PKG_CONFIG_PATH");
+        assert_eq!(result.unwrap().to_str().unwrap(), "/target/path");
+    }
+
+    #[test]
+    fn env_for_target_falls_back_to_global_var() {
+        env::set_var("PKG_CONFIG_LIBDIR", "/global/libdir");
+        let result = env_for_target("PKG_CONFIG_LIBDIR", &None);
+        env::remove_var("PKG_CONFIG_LIBDIR");
+        assert_eq!(result.unwrap().to_str().unwrap(), "/global/libdir");
+    }
+
+    #[test]
+    fn shell_split_handles_backslash_escaped_spaces() {
+        let words = shell_split(r"-I/opt/My\ SDK/include -L/opt/My\ SDK/lib -lfoo");
+        assert_eq!(words, vec![
+            "-I/opt/My SDK/include".to_string(),
+            "-L/opt/My SDK/lib".to_string(),
+            "-lfoo".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn shell_split_handles_single_quoted_spans() {
+        let words = shell_split("-I'/opt/My SDK/include' -lfoo");
+        assert_eq!(words, vec![
+            "-I/opt/My SDK/include".to_string(),
+            "-lfoo".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn shell_split_handles_double_quoted_escapes() {
+        let words = shell_split("-I\"/opt/My SDK/include\" -DFOO=\"a \\\"quoted\\\" value\"");
+        assert_eq!(words, vec![
+            "-I/opt/My SDK/include".to_string(),
+            "-DFOO=a \"quoted\" value".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn shell_split_collapses_consecutive_whitespace() {
+        let words = shell_split("-lfoo    -lbar\t-lbaz");
+        assert_eq!(words, vec!["-lfoo".to_string(), "-lbar".to_string(), "-lbaz".to_string()]);
+    }
+
+    fn words(s: &str) -> Vec<String> {
+        s.split(' ').map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn build_library_captures_valued_define() {
+        let (lib, _) = build_library(&words("-DFOO=bar -lfoo"), false);
+        assert_eq!(lib.defines.get("FOO"), Some(&Some("bar".to_string())));
+    }
+
+    #[test]
+    fn build_library_captures_valueless_define() {
+        let (lib, _) = build_library(&words("-DFOO -lfoo"), false);
+        assert_eq!(lib.defines.get("FOO"), Some(&None));
+    }
+
+    #[test]
+    fn build_library_collects_leftover_cflags_in_order() {
+        let (lib, _) = build_library(&words("-pthread -I/usr/include -std=c99 -lfoo"), false);
+        assert_eq!(lib.cflags, vec!["-pthread".to_string(), "-std=c99".to_string()]);
+    }
+
+    #[test]
+    fn build_library_excludes_known_flags_from_cflags() {
+        let (lib, _) = build_library(&words("-DFOO=bar -I/usr/include -L/usr/lib -lfoo"), false);
+        assert!(lib.cflags.is_empty());
+    }
+
+    #[test]
+    fn version_args_none_yields_no_constraint() {
+        assert!(version_args("foo", &None).is_empty());
+    }
+
+    #[test]
+    fn version_args_at_least() {
+        let version = Some(Version::AtLeast("1.2".to_string()));
+        assert_eq!(version_args("foo", &version), vec!["foo >= 1.2".to_string()]);
+    }
+
+    #[test]
+    fn version_args_exactly() {
+        let version = Some(Version::Exactly("1.2".to_string()));
+        assert_eq!(version_args("foo", &version), vec!["foo = 1.2".to_string()]);
+    }
+
+    #[test]
+    fn version_args_range_inclusive_low_exclusive_high() {
+        let version = Some(Version::Range(
+            Bound::Included("1.2".to_string()),
+            Bound::Excluded("2.0".to_string()),
+        ));
+        assert_eq!(version_args("foo", &version),
+                   vec!["foo >= 1.2".to_string(), "foo < 2.0".to_string()]);
+    }
+
+    #[test]
+    fn version_args_range_unbounded_low() {
+        let version = Some(Version::Range(
+            Bound::Unbounded,
+            Bound::Included("2.0".to_string()),
+        ));
+        assert_eq!(version_args("foo", &version), vec!["foo <= 2.0".to_string()]);
+    }
+
+    #[test]
+    fn range_version_overrides_earlier_atleast_version() {
+        let mut config = Config::new();
+        config.atleast_version("1.0");
+        config.range_version(Bound::Included("1.2"), Bound::Excluded("2.0"));
+        assert_eq!(version_args("foo", &config.version),
+                   vec!["foo >= 1.2".to_string(), "foo < 2.0".to_string()]);
+    }
+
+    #[test]
+    fn atleast_version_overrides_earlier_range_version() {
+        let mut config = Config::new();
+        config.range_version(Bound::Included("1.2"), Bound::Excluded("2.0"));
+        config.atleast_version("1.0");
+        assert_eq!(version_args("foo", &config.version), vec!["foo >= 1.0".to_string()]);
+    }
+
+    #[test]
+    fn env_vars_consulted_includes_name_and_global_vars() {
+        let vars = env_vars_consulted("foo", &None);
+        assert!(vars.contains(&"FOO_NO_PKG_CONFIG".to_string()));
+        assert!(vars.contains(&"FOO_STATIC".to_string()));
+        assert!(vars.contains(&"FOO_DYNAMIC".to_string()));
+        assert!(vars.contains(&"PKG_CONFIG".to_string()));
+        assert!(vars.contains(&"PKG_CONFIG_ALLOW_CROSS".to_string()));
+        assert!(vars.contains(&"PKG_CONFIG_PATH".to_string()));
+    }
+
+    #[test]
+    fn env_vars_consulted_adds_target_specific_vars() {
+        let target = Some("my-test-target-unique-4".to_string());
+        let vars = env_vars_consulted("foo", &target);
+        assert!(vars.contains(&"MY_TEST_TARGET_UNIQUE_4_PKG_CONFIG".to_string()));
+        assert!(vars.contains(&"PKG_CONFIG_MY_TEST_TARGET_UNIQUE_4".to_string()));
+        assert!(vars.contains(&"MY_TEST_TARGET_UNIQUE_4_PKG_CONFIG_PATH".to_string()));
+        assert!(vars.contains(&"PKG_CONFIG_PATH_MY_TEST_TARGET_UNIQUE_4".to_string()));
+    }
+
+    #[test]
+    fn config_defaults_to_printing_metadata_and_not_env_metadata() {
+        let config = Config::new();
+        assert_eq!(config.cargo_metadata, true);
+        assert_eq!(config.env_metadata, false);
+    }
+
+    #[test]
+    fn build_library_splits_wl_rpath_into_ld_args() {
+        let (lib, _) = build_library(
+            &words("-Wl,-rpath,/opt/foo/lib -L/opt/foo/lib -lfoo"), false);
+        assert_eq!(lib.ld_args, vec![
+            vec!["-Wl".to_string(), "-rpath".to_string(), "/opt/foo/lib".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn build_library_emits_rustc_link_arg_for_ld_args() {
+        let (_, metadata) = build_library(
+            &words("-Wl,-rpath,/opt/foo/lib -L/opt/foo/lib -lfoo"), false);
+        assert!(metadata.contains(&"rustc-link-arg=-Wl,-rpath,/opt/foo/lib".to_string()));
+    }
+
+    #[test]
+    fn build_library_excludes_wl_args_from_cflags() {
+        let (lib, _) = build_library(
+            &words("-Wl,-rpath,/opt/foo/lib -L/opt/foo/lib -lfoo"), false);
+        assert!(lib.cflags.is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+extern crate pkg_config;
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn pkg_config_available() -> bool {
+    Command::new("pkg-config").arg("--version").output().is_ok()
+}
+
+/// Regression test for `-Wl,-rpath,...` handling: the linker args must
+/// survive into `Library::ld_args` rather than being dropped as cflags.
+#[test]
+fn captures_rpath_as_ld_args() {
+    if !pkg_config_available() {
+        println!("skipping: pkg-config is not installed");
+        return;
+    }
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    env::set_var("PKG_CONFIG_PATH", &fixtures);
+
+    let library = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .find("rpath")
+        .unwrap();
+
+    assert!(library.ld_args.iter().any(|arg| {
+        arg.len() == 3 && arg[0] == "-Wl" && arg[1] == "-rpath" &&
+            arg[2].contains("/opt/foo/lib")
+    }));
+}
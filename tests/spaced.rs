@@ -0,0 +1,33 @@
+extern crate pkg_config;
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn pkg_config_available() -> bool {
+    Command::new("pkg-config").arg("--version").output().is_ok()
+}
+
+/// Regression test for the shell-aware tokenizer: a `.pc` file whose
+/// `prefix` contains a space must still yield intact `-I`/`-L` paths.
+#[test]
+fn finds_spaced_include_and_lib_paths() {
+    if !pkg_config_available() {
+        println!("skipping: pkg-config is not installed");
+        return;
+    }
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    env::set_var("PKG_CONFIG_PATH", &fixtures);
+
+    let library = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .find("spaced")
+        .unwrap();
+
+    assert!(library.include_paths.iter()
+        .any(|p| p.to_str().unwrap().contains("My SDK/include")));
+    assert!(library.link_paths.iter()
+        .any(|p| p.to_str().unwrap().contains("My SDK/lib")));
+    assert!(library.libs.iter().any(|l| l == "spaced"));
+}